@@ -12,40 +12,59 @@ use std::sync::{Mutex, OnceLock};
  * See the Mulan PSL v2 for more details.
  */
 use crate::harness::get_cover_number;
+use libafl_bolts::shmem::{ShMem, ShMemProvider, StdShMemProvider};
 
+/// The raw hit-count map and its accumulated union, both backed by an actual shared-memory
+/// region rather than a plain heap buffer. Under `--fork` the harness runs in a forked child,
+/// and a private heap allocation can't carry the child's writes back to the parent once it's
+/// reaped; a `ShMem` mapping is inherited across `fork()`, so the child writes the same physical
+/// pages the parent reads. Under `--fuzzing` each `Launcher` client calls `cover_init` after it
+/// has been forked, so every core gets its own region rather than sharing one.
 struct Coverage {
-    cover_points: Vec<u8>,
-    accumulated: Vec<u8>,
+    cover_points: <StdShMemProvider as ShMemProvider>::Mem,
+    accumulated: <StdShMemProvider as ShMemProvider>::Mem,
 }
 
 impl Coverage {
     pub fn new(n_cover: usize) -> Self {
+        let mut shmem_provider =
+            StdShMemProvider::new().expect("Failed to init shared memory provider for coverage");
+        let mut cover_points = shmem_provider
+            .new_shmem(n_cover)
+            .expect("Failed to allocate shared coverage map");
+        let mut accumulated = shmem_provider
+            .new_shmem(n_cover)
+            .expect("Failed to allocate shared accumulated coverage map");
+        cover_points.as_slice_mut().fill(0);
+        accumulated.as_slice_mut().fill(0);
         Self {
-            cover_points: vec![0; n_cover],
-            accumulated: vec![0; n_cover],
+            cover_points,
+            accumulated,
         }
     }
 
     pub fn len(&self) -> usize {
-        self.cover_points.capacity()
+        self.cover_points.as_slice().len()
     }
 
-    pub fn as_mut_ptr(&self) -> *mut u8 {
-        self.cover_points.as_ptr().cast_mut()
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.cover_points.as_slice_mut().as_mut_ptr()
     }
 
     pub fn accumulate(&mut self) {
-        for (i, covered) in self.cover_points.iter().enumerate() {
-            if *covered != 0 as u8 {
-                self.accumulated[i] = 1;
+        let hit: Vec<u8> = self.cover_points.as_slice().to_vec();
+        let accumulated = self.accumulated.as_slice_mut();
+        for (i, covered) in hit.iter().enumerate() {
+            if *covered != 0 {
+                accumulated[i] = 1;
             }
         }
     }
 
     pub fn get_accumulative_coverage(&self) -> f64 {
         let mut covered_num: usize = 0;
-        for covered in self.accumulated.iter() {
-            if *covered != 0 as u8 {
+        for covered in self.accumulated.as_slice().iter() {
+            if *covered != 0 {
                 covered_num += 1;
             }
         }
@@ -59,6 +78,18 @@ impl Coverage {
             self.get_accumulative_coverage()
         );
     }
+
+    /// Indices hit by the run that just finished (i.e. nonzero in the raw, non-accumulated
+    /// map). Used by corpus minimization to see which points a single testcase covers.
+    pub fn covered_indices(&self) -> Vec<usize> {
+        self.cover_points
+            .as_slice()
+            .iter()
+            .enumerate()
+            .filter(|(_, covered)| **covered != 0)
+            .map(|(i, _)| i)
+            .collect()
+    }
 }
 
 static ICOVERAGE: OnceLock<Mutex<Coverage>> = OnceLock::new();
@@ -85,7 +116,7 @@ pub(crate) fn cover_len() -> usize {
 }
 
 pub(crate) fn cover_as_mut_ptr() -> *mut u8 {
-    let guard = cov();
+    let mut guard = cov();
     guard.as_mut_ptr().cast::<u8>()
 }
 
@@ -96,3 +127,7 @@ pub(crate) fn cover_accumulate() {
 pub(crate) fn cover_display() {
     cov().display()
 }
+
+pub(crate) fn cover_covered_indices() -> Vec<usize> {
+    cov().covered_indices()
+}
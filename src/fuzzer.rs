@@ -15,73 +15,280 @@ use std::time::Duration;
 
 use crate::coverage::*;
 use crate::harness;
+use crate::instr_input::{
+    InstrSpliceMutator, InstrStreamGenerator, InstrStreamInput, InstrDeleteMutator,
+    InstrDuplicateMutator, InstrInsertMutator, InterestingImmMutator, OpcodeClassMutator,
+};
 use crate::monitor;
 
 use libafl::StdFuzzer;
+use libafl::events::EventConfig;
+use libafl::observers::HitcountsMapObserver;
 use libafl::prelude::*;
-use libafl::schedulers::QueueScheduler;
-use libafl::stages::StdMutationalStage;
+use libafl::schedulers::powersched::PowerSchedule;
+use libafl::schedulers::{IndexesLenTimeMinimizerScheduler, StdWeightedScheduler};
+use libafl::stages::{CalibrationStage, StdPowerMutationalStage};
 use libafl::state::StdState;
+use libafl_bolts::core_affinity::Cores;
+use libafl_bolts::launcher::Launcher;
+use libafl_bolts::shmem::{ShMemProvider, StdShMemProvider};
 use libafl_bolts::{current_nanos, rands::StdRand, tuples::tuple_list};
 
-pub(crate) fn run_fuzzer(
-    random_input: bool,
-    max_iters: Option<u64>,
-    max_run_timeout: Option<u64>,
-    corpus_input: Option<String>,
-    corpus_output: Option<String>,
-    continue_on_errors: bool,
-    save_errors: bool,
-) {
-    // Scheduler, Feedback, Objective
-    let scheduler = QueueScheduler::new();
-    let observer =
-        unsafe { StdMapObserver::from_mut_ptr("signals", cover_as_mut_ptr(), cover_len()) };
-    let mut feedback = MaxMapFeedback::new(&observer);
-    let mut objective = CrashFeedback::new();
-
-    // State, Manager
-    let mut state = StdState::new(
-        StdRand::with_seed(current_nanos()),
-        InMemoryCorpus::new(),
-        OnDiskCorpus::new(PathBuf::from("./crashes")).unwrap(),
-        &mut feedback,
-        &mut objective,
-    )
-    .unwrap();
-    let monitor = SimpleMonitor::new(|s| {
-        println!("{}", s);
-    });
-    let mut mgr = SimpleEventManager::new(monitor);
-
-    // Fuzzer, Executor
-    let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
-    let mut binding = harness::fuzz_harness;
-    let mut executor = InProcessExecutor::with_timeout(
-        &mut binding,
-        // tuple_list!(edges_observer, time_observer),
-        tuple_list!(observer),
-        &mut fuzzer,
-        &mut state,
-        &mut mgr,
-        Duration::from_secs(max_run_timeout.unwrap_or(10)),
-    )
-    .unwrap();
-
-    if continue_on_errors {
-        unsafe { harness::CONTINUE_ON_ERRORS = true };
+/// Which corpus-scheduling strategy `--schedule` should use. `Queue` is handled exactly like
+/// `Explore`: the weighted scheduler with the uninformed `EXPLORE` power schedule, which does
+/// not walk the corpus in insertion order. `Fast`, `Coe`, `Lin` and `Quad` bias energy toward
+/// seeds that are fast and find fresh coverage.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Schedule {
+    Queue,
+    Explore,
+    Fast,
+    Coe,
+    Lin,
+    Quad,
+}
+
+impl Schedule {
+    pub(crate) fn parse(name: &str) -> Self {
+        match name {
+            "queue" => Schedule::Queue,
+            "explore" => Schedule::Explore,
+            "fast" => Schedule::Fast,
+            "coe" => Schedule::Coe,
+            "lin" => Schedule::Lin,
+            "quad" => Schedule::Quad,
+            other => panic!(
+                "Unknown --schedule {:?}, expected one of queue, explore, fast, coe, lin, quad",
+                other
+            ),
+        }
+    }
+
+    fn power_schedule(self) -> PowerSchedule {
+        match self {
+            Schedule::Queue | Schedule::Explore => PowerSchedule::EXPLORE,
+            Schedule::Fast => PowerSchedule::FAST,
+            Schedule::Coe => PowerSchedule::COE,
+            Schedule::Lin => PowerSchedule::LIN,
+            Schedule::Quad => PowerSchedule::QUAD,
+        }
+    }
+}
+
+/// Which `Input` implementation the fuzzer feeds to the simulator. `Bytes` is the long-standing
+/// default; `Instr` mutates a structured RISC-V instruction stream instead of raw bytes so
+/// mutated workloads stay decodable under `--coverage instr-imm`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum InputFormat {
+    Bytes,
+    Instr,
+}
+
+impl InputFormat {
+    pub(crate) fn parse(name: &str) -> Self {
+        match name {
+            "bytes" => InputFormat::Bytes,
+            "instr" => InputFormat::Instr,
+            other => panic!("Unknown --input-format {:?}, expected one of bytes, instr", other),
+        }
     }
+}
+
+/// Everything `run_fuzzer` needs, bundled up because the bytes and instruction-stream fuzzing
+/// loops both need the full set and Rust can't share one generic closure across two distinct
+/// `Input` types.
+pub(crate) struct FuzzerConfig {
+    pub(crate) random_input: bool,
+    pub(crate) max_iters: Option<u64>,
+    /// Caps the total number of harness executions (`state.executions()`), tracked by the
+    /// `Fuzzer` in the parent process so it still works when `--fork` runs each execution in a
+    /// throwaway child. Distinct from `max_iters`, which caps fuzzer loop iterations instead.
+    pub(crate) max_runs: Option<u64>,
+    pub(crate) max_run_timeout: Option<u64>,
+    pub(crate) corpus_input: Option<String>,
+    pub(crate) corpus_output: Option<String>,
+    pub(crate) continue_on_errors: bool,
+    pub(crate) save_errors: bool,
+    pub(crate) schedule: Schedule,
+    pub(crate) cores: Cores,
+    pub(crate) broker_port: u16,
+    /// Run each simulation in a forked child (`InProcessForkExecutor`) instead of in-process,
+    /// so a segfault or assertion inside `sim_main` is reaped as an `ExitKind::Crash` objective
+    /// rather than taking the whole worker down.
+    pub(crate) fork: bool,
+    /// Run greedy weighted set-cover corpus minimization before writing `corpus_output`.
+    pub(crate) cmin: bool,
+}
+
+pub(crate) fn run_fuzzer(config: FuzzerConfig, input_format: InputFormat) {
+    match input_format {
+        InputFormat::Bytes => run_bytes_fuzzer(config),
+        InputFormat::Instr => run_instr_fuzzer(config),
+    }
+}
+
+fn run_bytes_fuzzer(config: FuzzerConfig) {
+    let FuzzerConfig {
+        random_input,
+        max_iters,
+        max_runs,
+        max_run_timeout,
+        corpus_input,
+        corpus_output,
+        continue_on_errors,
+        save_errors,
+        schedule,
+        cores,
+        broker_port,
+        fork,
+        cmin,
+    } = config;
+
+    let shmem_provider = StdShMemProvider::new().expect("Failed to init shared memory provider");
+    let monitor = MultiMonitor::new(|s| println!("{}", s));
+
+    let mut run_client = |state: Option<StdState<_, _, _, _>>,
+                          mut mgr: LlmpRestartingEventManager<_, _, _>,
+                          _core_id: CoreId| {
+        // Each core is its own forked client process (Launcher forks on Unix), so coverage is
+        // initialized here rather than before `launch()`: that gives every core its own
+        // independent `ShMem` region instead of all of them racing on the same one. Idempotent
+        // across restarts within this process since `cover_init` ignores an already-set map.
+        cover_init();
+
+        // Feedback, Objective
+        // Wrap the raw hit-count map in the classic AFL bucket classifier (0, 1, 2, 4, 8, 16,
+        // 32, 64, 128+) so that a counter going from "hit once" to "hit a thousand times"
+        // registers as new coverage instead of being indistinguishable from the first hit.
+        let observer = HitcountsMapObserver::new(unsafe {
+            StdMapObserver::from_mut_ptr("signals", cover_as_mut_ptr(), cover_len())
+        });
+        let time_observer = TimeObserver::new("time");
+        let map_feedback = MaxMapFeedback::new(&observer);
+        let calibration = CalibrationStage::new(&map_feedback);
+        let mut feedback = feedback_or!(map_feedback, TimeFeedback::new(&time_observer));
+        let mut objective = CrashFeedback::new();
+
+        // State: reuse the state LLMP restored for us after a restart, otherwise start fresh.
+        let mut state = match state {
+            Some(state) => state,
+            None => StdState::new(
+                StdRand::with_seed(current_nanos()),
+                InMemoryCorpus::new(),
+                OnDiskCorpus::new(PathBuf::from("./crashes")).unwrap(),
+                &mut feedback,
+                &mut objective,
+            )
+            .unwrap(),
+        };
 
-    if save_errors {
-        unsafe { harness::SAVE_ERRORS = true };
+        // Scheduler: bias energy toward fast, novelty-finding seeds instead of plain FIFO.
+        let weighted = StdWeightedScheduler::with_schedule(
+            &mut state,
+            &observer,
+            Some(schedule.power_schedule()),
+        );
+        let scheduler = IndexesLenTimeMinimizerScheduler::new(&observer, weighted);
+
+        // Fuzzer
+        let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
+        let mut binding = harness::fuzz_harness;
+
+        if continue_on_errors {
+            unsafe { harness::CONTINUE_ON_ERRORS = true };
+        }
+
+        if save_errors {
+            unsafe { harness::SAVE_ERRORS = true };
+        }
+
+        if random_input {
+            println!("We are using random input bytes");
+            unsafe { harness::USE_RANDOM_INPUT = true };
+        }
+
+        // Mutator
+        let mutator = HavocScheduledMutator::new(havoc_mutations());
+        let mut stages = tuple_list!(calibration, StdPowerMutationalStage::new(mutator));
+        let timeout = Duration::from_secs(max_run_timeout.unwrap_or(10));
+
+        // Executor: in-process by default, or one forked child per run when `--fork` is set so
+        // a segfault/assertion inside `sim_main` is reaped as a `Crash` objective instead of
+        // taking the whole worker down. The coverage map (`cover_as_mut_ptr`) is backed by a
+        // `ShMem` region (see `coverage.rs`), which a forked child inherits and writes through
+        // directly, so the parent observes the child's counters as soon as it's reaped.
+        if fork {
+            let mut fork_shmem_provider = StdShMemProvider::new()
+                .expect("Failed to init shared memory provider for fork executor");
+            let mut executor = InProcessForkExecutor::new(
+                &mut binding,
+                tuple_list!(observer, time_observer),
+                &mut fuzzer,
+                &mut state,
+                &mut mgr,
+                timeout,
+                &mut fork_shmem_provider,
+            )
+            .unwrap();
+
+            load_or_generate_corpus(&mut fuzzer, &mut executor, &mut state, &mut mgr, &corpus_input);
+            drive_fuzz_loop(&mut fuzzer, &mut stages, &mut executor, &mut state, &mut mgr, max_iters, max_runs)?;
+        } else {
+            let mut executor = InProcessExecutor::with_timeout(
+                &mut binding,
+                tuple_list!(observer, time_observer),
+                &mut fuzzer,
+                &mut state,
+                &mut mgr,
+                timeout,
+            )
+            .unwrap();
+
+            load_or_generate_corpus(&mut fuzzer, &mut executor, &mut state, &mut mgr, &corpus_input);
+            drive_fuzz_loop(&mut fuzzer, &mut stages, &mut executor, &mut state, &mut mgr, max_iters, max_runs)?;
+        }
+
+        if let Some(corpus_output) = corpus_output.clone() {
+            monitor::store_testcases(&mut state, corpus_output, cmin);
+        }
+
+        Ok(())
+    };
+
+    match Launcher::builder()
+        .shmem_provider(shmem_provider)
+        .configuration(EventConfig::from_name("xfuzz"))
+        .monitor(monitor)
+        .run_client(&mut run_client)
+        .cores(&cores)
+        .broker_port(broker_port)
+        .build()
+        .launch()
+    {
+        Ok(()) => (),
+        Err(Error::ShuttingDown) => println!("Fuzzing stopped by user. Good bye."),
+        Err(err) => panic!("Failed to run launcher: {:?}", err),
     }
+}
 
-    // Corpus
+/// Loads the on-disk corpus named by `corpus_input` into `state`, or generates a fresh random
+/// one if none is configured. Shared between the in-process and forked executor paths since
+/// it only needs `&mut E` generically, not a concrete executor type.
+fn load_or_generate_corpus<E, EM, Z>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut <Z as UsesState>::State,
+    mgr: &mut EM,
+    corpus_input: &Option<String>,
+) where
+    Z: Evaluator<E, EM>,
+    <Z as UsesState>::State: HasCorpus,
+{
     if state.corpus().count() < 1 {
-        if corpus_input.is_some() {
-            let corpus_dirs = vec![PathBuf::from(corpus_input.unwrap())];
+        if let Some(corpus_input) = corpus_input.clone() {
+            let corpus_dirs = vec![PathBuf::from(corpus_input)];
             state
-                .load_initial_inputs_forced(&mut fuzzer, &mut executor, &mut mgr, &corpus_dirs)
+                .load_initial_inputs_forced(fuzzer, executor, mgr, &corpus_dirs)
                 .unwrap_or_else(|err| {
                     panic!(
                         "Failed to load initial corpus at {:?}: {:?}",
@@ -91,41 +298,215 @@ pub(crate) fn run_fuzzer(
         } else {
             let mut generator = RandBytesGenerator::new(NonZero::new(16384).unwrap());
             state
-                .generate_initial_inputs(&mut fuzzer, &mut executor, &mut generator, &mut mgr, 32)
+                .generate_initial_inputs(fuzzer, executor, &mut generator, mgr, 32)
                 .expect("Failed to generate the initial corpus");
         }
         println!("We imported {} inputs from disk.", state.corpus().count());
     }
+}
 
-    if random_input {
-        println!("We are using random input bytes");
-        unsafe { harness::USE_RANDOM_INPUT = true };
+/// Runs the fuzzer loop, honoring whichever of `max_iters`/`max_runs` is set. `max_iters` caps
+/// the number of fuzzer loop iterations (`fuzz_loop_for`, as before); `max_runs` instead caps
+/// the total number of harness executions via `state.executions()`, which the `Fuzzer` updates
+/// in the parent after every execution, so it keeps working when `--fork` runs each execution
+/// in a throwaway child (a process-local counter inside the harness would not).
+fn drive_fuzz_loop<E, EM, Z, ST>(
+    fuzzer: &mut Z,
+    stages: &mut ST,
+    executor: &mut E,
+    state: &mut <Z as UsesState>::State,
+    mgr: &mut EM,
+    max_iters: Option<u64>,
+    max_runs: Option<u64>,
+) -> Result<(), Error>
+where
+    Z: Fuzzer<E, EM, ST>,
+    <Z as UsesState>::State: HasExecutions,
+{
+    if let Some(max_iters) = max_iters {
+        println!("Running the Fuzzer for {} iterations.", max_iters);
+        fuzzer.fuzz_loop_for(stages, executor, state, mgr, max_iters)?;
+    } else if let Some(max_runs) = max_runs {
+        println!("Running the Fuzzer for {} total executions.", max_runs);
+        while state.executions() < max_runs {
+            fuzzer.fuzz_one(stages, executor, state, mgr)?;
+        }
+    } else {
+        println!("Running the Fuzzer for unlimited iterations.");
+        fuzzer.fuzz_loop(stages, executor, state, mgr)?;
     }
+    Ok(())
+}
+
+/// Maximum instructions a stream may grow to; keeps `InstrInsertMutator`/`InstrDuplicateMutator`
+/// from growing workloads without bound.
+const MAX_INSTR_STREAM_LEN: usize = 4096;
+
+fn run_instr_fuzzer(config: FuzzerConfig) {
+    let FuzzerConfig {
+        random_input: _,
+        max_iters,
+        max_runs,
+        max_run_timeout,
+        corpus_input,
+        corpus_output,
+        continue_on_errors,
+        save_errors,
+        schedule,
+        cores,
+        broker_port,
+        fork,
+        cmin,
+    } = config;
+
+    let shmem_provider = StdShMemProvider::new().expect("Failed to init shared memory provider");
+    let monitor = MultiMonitor::new(|s| println!("{}", s));
+
+    let mut run_client = |state: Option<StdState<_, _, _, _>>,
+                          mut mgr: LlmpRestartingEventManager<_, _, _>,
+                          _core_id: CoreId| {
+        // See the bytes-fuzzer `run_client` for why this is initialized here rather than
+        // before `launch()`: it gives each forked per-core client its own `ShMem` region.
+        cover_init();
+
+        let observer = HitcountsMapObserver::new(unsafe {
+            StdMapObserver::from_mut_ptr("signals", cover_as_mut_ptr(), cover_len())
+        });
+        let time_observer = TimeObserver::new("time");
+        let map_feedback = MaxMapFeedback::new(&observer);
+        let calibration = CalibrationStage::new(&map_feedback);
+        let mut feedback = feedback_or!(map_feedback, TimeFeedback::new(&time_observer));
+        let mut objective = CrashFeedback::new();
+
+        let mut state: StdState<InstrStreamInput, _, _, _> = match state {
+            Some(state) => state,
+            None => StdState::new(
+                StdRand::with_seed(current_nanos()),
+                InMemoryCorpus::new(),
+                OnDiskCorpus::new(PathBuf::from("./crashes")).unwrap(),
+                &mut feedback,
+                &mut objective,
+            )
+            .unwrap(),
+        };
+        state.set_max_size(MAX_INSTR_STREAM_LEN);
 
-    // Mutator
-    let mutator = HavocScheduledMutator::new(havoc_mutations());
-    let mut stages = tuple_list!(StdMutationalStage::new(mutator));
-
-    // Fuzzing Loop
-    if max_iters.is_some() {
-        println!("Running the Fuzzer for {} iterations.", max_iters.unwrap());
-        fuzzer
-            .fuzz_loop_for(
-                &mut stages,
-                &mut executor,
+        let weighted = StdWeightedScheduler::with_schedule(
+            &mut state,
+            &observer,
+            Some(schedule.power_schedule()),
+        );
+        let scheduler = IndexesLenTimeMinimizerScheduler::new(&observer, weighted);
+
+        let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
+        let mut binding = harness::fuzz_harness_instr;
+
+        if continue_on_errors {
+            unsafe { harness::CONTINUE_ON_ERRORS = true };
+        }
+
+        if save_errors {
+            unsafe { harness::SAVE_ERRORS = true };
+        }
+
+        // Field-level mutators: swap opcodes within a legal class, perturb immediates with
+        // interesting values, insert/delete/duplicate whole instructions, and splice
+        // instruction runs between two corpus entries. This replaces `havoc_mutations()`,
+        // which mostly produces instructions that fail to decode.
+        let mutator = StdScheduledMutator::new(tuple_list!(
+            OpcodeClassMutator,
+            InterestingImmMutator,
+            InstrInsertMutator,
+            InstrDeleteMutator,
+            InstrDuplicateMutator,
+            InstrSpliceMutator,
+        ));
+        let mut stages = tuple_list!(calibration, StdPowerMutationalStage::new(mutator));
+        let timeout = Duration::from_secs(max_run_timeout.unwrap_or(10));
+
+        if fork {
+            let mut fork_shmem_provider = StdShMemProvider::new()
+                .expect("Failed to init shared memory provider for fork executor");
+            let mut executor = InProcessForkExecutor::new(
+                &mut binding,
+                tuple_list!(observer, time_observer),
+                &mut fuzzer,
                 &mut state,
                 &mut mgr,
-                max_iters.unwrap(),
+                timeout,
+                &mut fork_shmem_provider,
             )
-            .expect("Fuzzer should not run into errors.");
-    } else {
-        println!("Running the Fuzzer for unlimited iterations.");
-        fuzzer
-            .fuzz_loop(&mut stages, &mut executor, &mut state, &mut mgr)
-            .expect("Error in the fuzzing loop");
-    }
+            .unwrap();
+
+            load_or_generate_instr_corpus(&mut fuzzer, &mut executor, &mut state, &mut mgr, &corpus_input);
+            drive_fuzz_loop(&mut fuzzer, &mut stages, &mut executor, &mut state, &mut mgr, max_iters, max_runs)?;
+        } else {
+            let mut executor = InProcessExecutor::with_timeout(
+                &mut binding,
+                tuple_list!(observer, time_observer),
+                &mut fuzzer,
+                &mut state,
+                &mut mgr,
+                timeout,
+            )
+            .unwrap();
+
+            load_or_generate_instr_corpus(&mut fuzzer, &mut executor, &mut state, &mut mgr, &corpus_input);
+            drive_fuzz_loop(&mut fuzzer, &mut stages, &mut executor, &mut state, &mut mgr, max_iters, max_runs)?;
+        }
+
+        if let Some(corpus_output) = corpus_output.clone() {
+            monitor::store_instr_testcases(&mut state, corpus_output, cmin);
+        }
 
-    if corpus_output.is_some() {
-        monitor::store_testcases(&mut state, corpus_output.unwrap());
+        Ok(())
     };
+
+    match Launcher::builder()
+        .shmem_provider(shmem_provider)
+        .configuration(EventConfig::from_name("xfuzz"))
+        .monitor(monitor)
+        .run_client(&mut run_client)
+        .cores(&cores)
+        .broker_port(broker_port)
+        .build()
+        .launch()
+    {
+        Ok(()) => (),
+        Err(Error::ShuttingDown) => println!("Fuzzing stopped by user. Good bye."),
+        Err(err) => panic!("Failed to run launcher: {:?}", err),
+    }
+}
+
+/// Same as `load_or_generate_corpus`, but seeds a fresh corpus with `InstrStreamGenerator`
+/// instead of raw random bytes.
+fn load_or_generate_instr_corpus<E, EM, Z>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut <Z as UsesState>::State,
+    mgr: &mut EM,
+    corpus_input: &Option<String>,
+) where
+    Z: Evaluator<E, EM>,
+    <Z as UsesState>::State: HasCorpus,
+{
+    if state.corpus().count() < 1 {
+        if let Some(corpus_input) = corpus_input.clone() {
+            let corpus_dirs = vec![PathBuf::from(corpus_input)];
+            state
+                .load_initial_inputs_forced(fuzzer, executor, mgr, &corpus_dirs)
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Failed to load initial corpus at {:?}: {:?}",
+                        &corpus_dirs, err
+                    )
+                });
+        } else {
+            let mut generator = InstrStreamGenerator::new(64);
+            state
+                .generate_initial_inputs(fuzzer, executor, &mut generator, mgr, 32)
+                .expect("Failed to generate the initial corpus");
+        }
+        println!("We imported {} inputs from disk.", state.corpus().count());
+    }
 }
@@ -18,6 +18,7 @@ use std::ffi::CString;
 use std::io::{self, Write};
 
 use crate::coverage::*;
+use crate::instr_input::InstrStreamInput;
 use crate::monitor::store_testcase;
 
 use libafl::prelude::*;
@@ -70,9 +71,8 @@ fn sim_run(workload: &String) -> i32 {
     ret
 }
 
-fn sim_run_from_memory(input: &BytesInput) -> i32 {
+fn sim_run_from_bytes(wim_bytes: &[u8]) -> i32 {
     // create a workload-in-memory name for the input bytes
-    let wim_bytes = input.mutator_bytes();
     let wim_addr = wim_bytes.as_ptr();
     let wim_size = wim_bytes.len() as u64;
     let wim_name = format!("wim@{wim_addr:p}+0x{wim_size:x}");
@@ -80,6 +80,26 @@ fn sim_run_from_memory(input: &BytesInput) -> i32 {
     sim_run(&wim_name)
 }
 
+fn sim_run_from_memory(input: &BytesInput) -> i32 {
+    sim_run_from_bytes(input.mutator_bytes())
+}
+
+fn sim_run_from_instr_stream(input: &InstrStreamInput) -> i32 {
+    sim_run_from_bytes(&input.to_flat_image())
+}
+
+/// Replay a testcase and return the coverage indices it hits, for corpus minimization.
+pub(crate) fn replay_bytes_coverage(input: &BytesInput) -> Vec<usize> {
+    sim_run_from_memory(input);
+    cover_covered_indices()
+}
+
+/// Same as `replay_bytes_coverage`, for the `--input-format instr` corpus.
+pub(crate) fn replay_instr_coverage(input: &InstrStreamInput) -> Vec<usize> {
+    sim_run_from_instr_stream(input);
+    cover_covered_indices()
+}
+
 pub(crate) fn sim_run_multiple(workloads: &Vec<String>, auto_exit: bool) -> i32 {
     let mut ret = 0;
     for workload in workloads.iter() {
@@ -97,18 +117,17 @@ pub(crate) fn sim_run_multiple(workloads: &Vec<String>, auto_exit: bool) -> i32
 pub static mut USE_RANDOM_INPUT: bool = false;
 pub static mut CONTINUE_ON_ERRORS: bool = false;
 pub static mut SAVE_ERRORS: bool = false;
-pub static mut NUM_RUNS: u64 = 0;
-pub static mut MAX_RUNS: u64 = u64::MAX;
-
-pub(crate) fn fuzz_harness(input: &BytesInput) -> ExitKind {
-    let ret = if unsafe { USE_RANDOM_INPUT } {
-        let random_bytes: Vec<u8> = (0..1024).map(|_| rand::random::<u8>()).collect();
-        let b = BytesInput::new(random_bytes);
-        sim_run_from_memory(&b)
-    } else {
-        sim_run_from_memory(input)
-    };
 
+/// Post-run bookkeeping shared by every harness flavor: report coverage, honor
+/// `CONTINUE_ON_ERRORS`/`SAVE_ERRORS`, and decide the `ExitKind`. `save_error` is only invoked
+/// when the run failed and `SAVE_ERRORS` is set, so callers can defer serializing their
+/// (possibly non-`BytesInput`) testcase until it's actually needed.
+///
+/// `--max-runs` is *not* enforced here: under `--fork`, this function runs in a throwaway
+/// forked child, and a process-local counter would never see more than one execution before
+/// the child exits. `fuzzer::drive_fuzz_loop` caps the loop from the parent instead, off
+/// `state.executions()`, which the `Fuzzer` updates after every execution regardless of fork.
+fn finish_sim_run(ret: i32, save_error: impl FnOnce()) -> ExitKind {
     // get coverage
     cover_display();
     io::stdout().flush().unwrap();
@@ -123,27 +142,45 @@ pub(crate) fn fuzz_harness(input: &BytesInput) -> ExitKind {
     // save the target testcase into disk
     let do_save = unsafe { SAVE_ERRORS && ret != 0 };
     if do_save {
-        store_testcase(input, &"errors".to_string(), None);
-    }
-
-    // panic to exit the fuzzer if max_runs is reached
-    unsafe { NUM_RUNS += 1 };
-    let do_exit = unsafe { NUM_RUNS >= MAX_RUNS };
-    if do_exit {
-        println!("Exit due to max_runs == 0");
-        unsafe { display_uncovered_points() }
-        panic!("Exit due to max_runs == 0");
+        save_error();
     }
 
     ExitKind::Ok
 }
 
-pub(crate) fn set_sim_env(
-    coverage: String,
-    verbose: bool,
-    max_runs: Option<u64>,
-    emu_args: Vec<String>,
-) {
+pub(crate) fn fuzz_harness(input: &BytesInput) -> ExitKind {
+    let ret = if unsafe { USE_RANDOM_INPUT } {
+        let random_bytes: Vec<u8> = (0..1024).map(|_| rand::random::<u8>()).collect();
+        let b = BytesInput::new(random_bytes);
+        sim_run_from_memory(&b)
+    } else {
+        sim_run_from_memory(input)
+    };
+
+    finish_sim_run(ret, || store_testcase(input, &"errors".to_string(), None))
+}
+
+/// Same as `fuzz_harness`, but for the structured `--input-format instr` instruction-stream
+/// input: it is serialized to a flat image before being handed to the simulator.
+pub(crate) fn fuzz_harness_instr(input: &InstrStreamInput) -> ExitKind {
+    let ret = sim_run_from_instr_stream(input);
+
+    finish_sim_run(ret, || {
+        store_testcase(
+            &BytesInput::new(input.to_flat_image()),
+            &"errors".to_string(),
+            None,
+        )
+    })
+}
+
+/// Does *not* call `cover_init()`: under `--fuzzing`, `Launcher` forks one client process per
+/// `--cores` entry, and a `ShMem` region created here (before that fork) would have all cores
+/// inheriting the same physical pages and racing to write/read each other's hit-counts. Callers
+/// are responsible for initializing coverage at the right scope — once after this call (in
+/// `main`) for the direct (non-fuzzing) run path, or once per core inside each `run_client` for
+/// the fuzzing path.
+pub(crate) fn set_sim_env(coverage: String, verbose: bool, emu_args: Vec<String>) {
     let cover_name = CString::new(coverage.as_bytes()).unwrap();
     unsafe { set_cover_feedback(cover_name.as_ptr()) }
 
@@ -153,11 +190,5 @@ pub(crate) fn set_sim_env(
         unsafe { disable_sim_verbose() }
     }
 
-    if max_runs.is_some() {
-        unsafe { MAX_RUNS = max_runs.unwrap() };
-    }
-
     let _ = SIM_ARGS.set(Mutex::new(emu_args));
-
-    cover_init();
 }
@@ -0,0 +1,505 @@
+/**
+ * Copyright (c) 2023 Institute of Computing Technology, Chinese Academy of Sciences
+ * xfuzz is licensed under Mulan PSL v2.
+ * You can use this software according to the terms and conditions of the Mulan PSL v2.
+ * You may obtain a copy of Mulan PSL v2 at:
+ *          http://license.coscl.org.cn/MulanPSL2
+ * THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+ * EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+ * MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+ * See the Mulan PSL v2 for more details.
+ */
+use libafl::corpus::CorpusId;
+use libafl::generators::Generator;
+use libafl::inputs::Input;
+use libafl::mutators::{MutationResult, Mutator};
+use libafl::state::{HasMaxSize, HasRand};
+use libafl::Error;
+use libafl_bolts::rands::Rand;
+use libafl_bolts::tuples::Named;
+use libafl_bolts::HasLen;
+use serde::{Deserialize, Serialize};
+
+/// One decoded RISC-V instruction, field-addressable so mutators can perturb a single field
+/// (e.g. just the immediate) instead of flipping raw bytes that usually decode to garbage.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Instruction {
+    pub opcode: u8,
+    pub rd: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    pub funct: u8,
+    pub imm: i32,
+}
+
+/// Opcodes that are legal to swap with one another because they share an encoding shape
+/// (same operand layout), grouped by "class" so `OpcodeClassMutator` never turns e.g. an
+/// R-type ALU op into an I-type load and produce something that can't even be decoded.
+const OPCODE_CLASSES: &[&[u8]] = &[
+    &[0x33],       // OP: R-type ALU (add, sub, and, or, xor, ...)
+    &[0x13, 0x67], // OP-IMM / JALR: I-type, share the same operand layout
+    &[0x03],       // LOAD: I-type
+    &[0x23],       // STORE: S-type
+    &[0x63],       // BRANCH: B-type
+    &[0x6f],       // JAL: J-type, no other opcode shares its layout
+];
+
+/// The RISC-V base-ISA instruction formats, each of which places its immediate (if any) in a
+/// different set of bit ranges. `Instruction::encode`/`decode` dispatch on this instead of
+/// using one shared layout, so immediates actually land where each opcode's real decoder
+/// expects them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    R,
+    I,
+    S,
+    B,
+    J,
+}
+
+impl Format {
+    fn of(opcode: u8) -> Self {
+        match opcode {
+            0x33 => Format::R,
+            0x13 | 0x03 | 0x67 => Format::I,
+            0x23 => Format::S,
+            0x63 => Format::B,
+            0x6f => Format::J,
+            _ => Format::R,
+        }
+    }
+}
+
+/// Sign-extends the low `bits` bits of `value` to a full `i32`.
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+/// Immediate values that are disproportionately likely to trigger edge cases (AFL's classic
+/// "interesting values" list, narrowed to what fits a RISC-V 12/20-bit immediate).
+const INTERESTING_IMMS: &[i32] = &[
+    0,
+    1,
+    -1,
+    i8::MIN as i32,
+    i8::MAX as i32,
+    i16::MIN as i32,
+    i16::MAX as i32,
+    -2048,
+    2047,
+];
+
+impl Instruction {
+    /// Encodes this instruction into its 32-bit RISC-V word, using the bit layout of the
+    /// format its opcode belongs to (R/I/S/B/J) rather than one shared layout. Fields that the
+    /// format doesn't carry (e.g. `rs2` on an I-type) are simply ignored.
+    fn encode(&self) -> u32 {
+        let opcode = self.opcode as u32 & 0x7f;
+        let rd = self.rd as u32 & 0x1f;
+        let rs1 = self.rs1 as u32 & 0x1f;
+        let rs2 = self.rs2 as u32 & 0x1f;
+        let funct3 = self.funct as u32 & 0x7;
+        match Format::of(self.opcode) {
+            Format::R => {
+                let funct7 = self.imm as u32 & 0x7f;
+                opcode | (rd << 7) | (funct3 << 12) | (rs1 << 15) | (rs2 << 20) | (funct7 << 25)
+            }
+            Format::I => {
+                let imm = self.imm as u32 & 0xfff;
+                opcode | (rd << 7) | (funct3 << 12) | (rs1 << 15) | (imm << 20)
+            }
+            Format::S => {
+                let imm = self.imm as u32 & 0xfff;
+                let imm_lo = imm & 0x1f;
+                let imm_hi = (imm >> 5) & 0x7f;
+                opcode | (imm_lo << 7) | (funct3 << 12) | (rs1 << 15) | (rs2 << 20) | (imm_hi << 25)
+            }
+            Format::B => {
+                // 13-bit signed offset, bit 0 implicitly zero (branch targets are 2-aligned).
+                let imm = self.imm as u32 & 0x1fff;
+                let imm_11 = (imm >> 11) & 0x1;
+                let imm_4_1 = (imm >> 1) & 0xf;
+                let imm_10_5 = (imm >> 5) & 0x3f;
+                let imm_12 = (imm >> 12) & 0x1;
+                opcode
+                    | (imm_11 << 7)
+                    | (imm_4_1 << 8)
+                    | (funct3 << 12)
+                    | (rs1 << 15)
+                    | (rs2 << 20)
+                    | (imm_10_5 << 25)
+                    | (imm_12 << 31)
+            }
+            Format::J => {
+                // 21-bit signed offset, bit 0 implicitly zero.
+                let imm = self.imm as u32 & 0x1fffff;
+                let imm_19_12 = (imm >> 12) & 0xff;
+                let imm_11 = (imm >> 11) & 0x1;
+                let imm_10_1 = (imm >> 1) & 0x3ff;
+                let imm_20 = (imm >> 20) & 0x1;
+                opcode | (rd << 7) | (imm_19_12 << 12) | (imm_11 << 20) | (imm_10_1 << 21) | (imm_20 << 31)
+            }
+        }
+    }
+
+    /// Decodes a 32-bit RISC-V word back into an `Instruction`, the inverse of `encode`. Fields
+    /// the word's format doesn't carry are zeroed.
+    fn decode(word: u32) -> Instruction {
+        let opcode = (word & 0x7f) as u8;
+        let rd = ((word >> 7) & 0x1f) as u8;
+        let funct3 = ((word >> 12) & 0x7) as u8;
+        let rs1 = ((word >> 15) & 0x1f) as u8;
+        let rs2 = ((word >> 20) & 0x1f) as u8;
+        match Format::of(opcode) {
+            Format::R => {
+                let funct7 = ((word >> 25) & 0x7f) as i32;
+                Instruction { opcode, rd, rs1, rs2, funct: funct3, imm: funct7 }
+            }
+            Format::I => {
+                let imm = sign_extend((word >> 20) & 0xfff, 12);
+                Instruction { opcode, rd, rs1, rs2: 0, funct: funct3, imm }
+            }
+            Format::S => {
+                let imm = ((word >> 7) & 0x1f) | (((word >> 25) & 0x7f) << 5);
+                Instruction { opcode, rd: 0, rs1, rs2, funct: funct3, imm: sign_extend(imm, 12) }
+            }
+            Format::B => {
+                let imm = (((word >> 8) & 0xf) << 1)
+                    | (((word >> 25) & 0x3f) << 5)
+                    | (((word >> 7) & 0x1) << 11)
+                    | (((word >> 31) & 0x1) << 12);
+                Instruction { opcode, rd: 0, rs1, rs2, funct: funct3, imm: sign_extend(imm, 13) }
+            }
+            Format::J => {
+                let imm = (((word >> 21) & 0x3ff) << 1)
+                    | (((word >> 20) & 0x1) << 11)
+                    | (((word >> 12) & 0xff) << 12)
+                    | (((word >> 31) & 0x1) << 20);
+                Instruction { opcode, rd, rs1: 0, rs2: 0, funct: 0, imm: sign_extend(imm, 21) }
+            }
+        }
+    }
+
+    fn opcode_class(opcode: u8) -> Option<usize> {
+        OPCODE_CLASSES
+            .iter()
+            .position(|class| class.contains(&opcode))
+    }
+}
+
+/// An ordered list of decoded RISC-V instructions, used as the fuzzer's `Input` in
+/// `--input-format instr` mode so mutations stay on instruction boundaries instead of
+/// scribbling over raw bytes that mostly decode to illegal or trapping opcodes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct InstrStreamInput {
+    instructions: Vec<Instruction>,
+}
+
+impl InstrStreamInput {
+    pub(crate) fn new(instructions: Vec<Instruction>) -> Self {
+        Self { instructions }
+    }
+
+    pub(crate) fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub(crate) fn instructions_mut(&mut self) -> &mut Vec<Instruction> {
+        &mut self.instructions
+    }
+
+    /// Serialize the instruction stream into a flat little-endian image, the same format
+    /// `harness::sim_run_from_memory` already hands the simulator for workload-in-memory runs.
+    pub(crate) fn to_flat_image(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.instructions.len() * 4);
+        for instr in &self.instructions {
+            bytes.extend_from_slice(&instr.encode().to_le_bytes());
+        }
+        bytes
+    }
+}
+
+impl Input for InstrStreamInput {
+    fn generate_name(&self, id: Option<CorpusId>) -> String {
+        match id {
+            Some(id) => format!("instr-{id}"),
+            None => format!("instr-{}", self.instructions.len()),
+        }
+    }
+}
+
+/// Required by the `StdWeightedScheduler`/`IndexesLenTimeMinimizerScheduler` power-schedule
+/// pipeline (same one `run_bytes_fuzzer` drives over `BytesInput`), which scores and minimizes
+/// testcases partly by input length.
+impl HasLen for InstrStreamInput {
+    fn len(&self) -> usize {
+        self.instructions.len()
+    }
+}
+
+fn rand_instruction<R: Rand>(rand: &mut R) -> Instruction {
+    let class = &OPCODE_CLASSES[rand.below(OPCODE_CLASSES.len() as u64) as usize];
+    Instruction {
+        opcode: class[rand.below(class.len() as u64) as usize],
+        rd: rand.below(32) as u8,
+        rs1: rand.below(32) as u8,
+        rs2: rand.below(32) as u8,
+        funct: rand.below(8) as u8,
+        imm: *INTERESTING_IMMS.get(rand.below(INTERESTING_IMMS.len() as u64) as usize).unwrap(),
+    }
+}
+
+/// Swap an instruction's opcode for another one in the same legal class, keeping the
+/// encoding shape (and therefore decodability) intact.
+#[derive(Debug, Default)]
+pub(crate) struct OpcodeClassMutator;
+
+impl Named for OpcodeClassMutator {
+    fn name(&self) -> &str {
+        "OpcodeClassMutator"
+    }
+}
+
+impl<S> Mutator<InstrStreamInput, S> for OpcodeClassMutator
+where
+    S: HasRand,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut InstrStreamInput) -> Result<MutationResult, Error> {
+        if input.instructions().is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        let idx = state.rand_mut().below(input.instructions().len() as u64) as usize;
+        let Some(class_idx) = Instruction::opcode_class(input.instructions()[idx].opcode) else {
+            return Ok(MutationResult::Skipped);
+        };
+        let class = OPCODE_CLASSES[class_idx];
+        let new_opcode = class[state.rand_mut().below(class.len() as u64) as usize];
+        input.instructions_mut()[idx].opcode = new_opcode;
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Perturb a single instruction's immediate with one of AFL's "interesting values".
+#[derive(Debug, Default)]
+pub(crate) struct InterestingImmMutator;
+
+impl Named for InterestingImmMutator {
+    fn name(&self) -> &str {
+        "InterestingImmMutator"
+    }
+}
+
+impl<S> Mutator<InstrStreamInput, S> for InterestingImmMutator
+where
+    S: HasRand,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut InstrStreamInput) -> Result<MutationResult, Error> {
+        if input.instructions().is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        let idx = state.rand_mut().below(input.instructions().len() as u64) as usize;
+        let imm = INTERESTING_IMMS[state.rand_mut().below(INTERESTING_IMMS.len() as u64) as usize];
+        input.instructions_mut()[idx].imm = imm;
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Insert a freshly generated, legally-encodable instruction at a random position.
+#[derive(Debug, Default)]
+pub(crate) struct InstrInsertMutator;
+
+impl Named for InstrInsertMutator {
+    fn name(&self) -> &str {
+        "InstrInsertMutator"
+    }
+}
+
+impl<S> Mutator<InstrStreamInput, S> for InstrInsertMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut InstrStreamInput) -> Result<MutationResult, Error> {
+        if input.instructions().len() >= state.max_size() {
+            return Ok(MutationResult::Skipped);
+        }
+        let idx = state.rand_mut().below(input.instructions().len() as u64 + 1) as usize;
+        let instr = rand_instruction(state.rand_mut());
+        input.instructions_mut().insert(idx, instr);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Delete a random instruction.
+#[derive(Debug, Default)]
+pub(crate) struct InstrDeleteMutator;
+
+impl Named for InstrDeleteMutator {
+    fn name(&self) -> &str {
+        "InstrDeleteMutator"
+    }
+}
+
+impl<S> Mutator<InstrStreamInput, S> for InstrDeleteMutator
+where
+    S: HasRand,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut InstrStreamInput) -> Result<MutationResult, Error> {
+        if input.instructions().is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        let idx = state.rand_mut().below(input.instructions().len() as u64) as usize;
+        input.instructions_mut().remove(idx);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Duplicate a random instruction right after itself.
+#[derive(Debug, Default)]
+pub(crate) struct InstrDuplicateMutator;
+
+impl Named for InstrDuplicateMutator {
+    fn name(&self) -> &str {
+        "InstrDuplicateMutator"
+    }
+}
+
+impl<S> Mutator<InstrStreamInput, S> for InstrDuplicateMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut InstrStreamInput) -> Result<MutationResult, Error> {
+        if input.instructions().is_empty() || input.instructions().len() >= state.max_size() {
+            return Ok(MutationResult::Skipped);
+        }
+        let idx = state.rand_mut().below(input.instructions().len() as u64) as usize;
+        let instr = input.instructions()[idx].clone();
+        input.instructions_mut().insert(idx, instr);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Splice a contiguous run of instructions from another corpus entry into this one, the
+/// instruction-level analogue of `havoc_mutations`'s byte splice.
+#[derive(Debug, Default)]
+pub(crate) struct InstrSpliceMutator;
+
+impl Named for InstrSpliceMutator {
+    fn name(&self) -> &str {
+        "InstrSpliceMutator"
+    }
+}
+
+impl<S> Mutator<InstrStreamInput, S> for InstrSpliceMutator
+where
+    S: HasRand + HasMaxSize + libafl::state::HasCorpus<Input = InstrStreamInput>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut InstrStreamInput) -> Result<MutationResult, Error> {
+        if state.corpus().count() < 2 {
+            return Ok(MutationResult::Skipped);
+        }
+        let id = state.corpus().random_id(state.rand_mut());
+        let other = state.corpus().get(id)?.borrow_mut().load_input(state.corpus())?.clone();
+        if other.instructions().is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let start = state.rand_mut().below(other.instructions().len() as u64) as usize;
+        let len = state.rand_mut().below((other.instructions().len() - start) as u64 + 1) as usize;
+        let splice = &other.instructions()[start..start + len];
+
+        let at = state.rand_mut().below(input.instructions().len() as u64 + 1) as usize;
+        input.instructions_mut().splice(at..at, splice.iter().cloned());
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Generates the initial corpus of random-but-legally-encodable instruction streams, used
+/// in place of `RandBytesGenerator` when `--input-format instr` is set and no corpus is
+/// loaded from disk.
+pub(crate) struct InstrStreamGenerator {
+    max_len: usize,
+}
+
+impl InstrStreamGenerator {
+    pub(crate) fn new(max_len: usize) -> Self {
+        Self { max_len }
+    }
+}
+
+impl<S> Generator<InstrStreamInput, S> for InstrStreamGenerator
+where
+    S: HasRand,
+{
+    fn generate(&mut self, state: &mut S) -> Result<InstrStreamInput, Error> {
+        let len = 1 + state.rand_mut().below(self.max_len as u64) as usize;
+        let instructions = (0..len).map(|_| rand_instruction(state.rand_mut())).collect();
+        Ok(InstrStreamInput::new(instructions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an `Instruction` with only the fields its format actually encodes set, so
+    /// `decode(encode(instr)) == instr` holds exactly (fields a format doesn't carry, e.g.
+    /// `rs2` on an I-type, are zeroed the same way `decode` zeroes them).
+    fn roundtrip_instruction(opcode: u8, rd: u8, rs1: u8, rs2: u8, funct: u8, imm: i32) -> Instruction {
+        match Format::of(opcode) {
+            Format::R => Instruction { opcode, rd, rs1, rs2, funct, imm: imm & 0x7f },
+            Format::I => Instruction { opcode, rd, rs1, rs2: 0, funct, imm: sign_extend(imm as u32, 12) },
+            Format::S => Instruction { opcode, rd: 0, rs1, rs2, funct, imm: sign_extend(imm as u32, 12) },
+            Format::B => Instruction {
+                opcode,
+                rd: 0,
+                rs1,
+                rs2,
+                funct,
+                imm: sign_extend(imm as u32 & !1, 13),
+            },
+            Format::J => Instruction {
+                opcode,
+                rd,
+                rs1: 0,
+                rs2: 0,
+                funct: 0,
+                imm: sign_extend(imm as u32 & !1, 21),
+            },
+        }
+    }
+
+    #[test]
+    fn encode_decode_roundtrips_every_opcode_class() {
+        for class in OPCODE_CLASSES {
+            for &opcode in *class {
+                let instr = roundtrip_instruction(opcode, 5, 9, 17, 3, -100);
+                let decoded = Instruction::decode(instr.encode());
+                assert_eq!(decoded, instr, "opcode 0x{opcode:02x} did not roundtrip");
+            }
+        }
+    }
+
+    #[test]
+    fn b_type_immediate_lands_in_its_own_bit_range() {
+        // A BRANCH (0x63) encoded with an I-type layout would put its immediate at bits
+        // [31:20]; the B-type layout instead splits it across [31],[30:25],[11:8],[7].
+        let instr = Instruction { opcode: 0x63, rd: 0, rs1: 1, rs2: 2, funct: 4, imm: -4 };
+        let word = instr.encode();
+        assert_eq!(Instruction::decode(word), instr);
+        assert_ne!(
+            (word >> 20) & 0xfff,
+            instr.imm as u32 & 0xfff,
+            "imm should not be sitting in the I-type slot"
+        );
+    }
+
+    #[test]
+    fn j_type_immediate_lands_in_its_own_bit_range() {
+        let instr = Instruction { opcode: 0x6f, rd: 3, rs1: 0, rs2: 0, funct: 0, imm: 4094 };
+        assert_eq!(Instruction::decode(instr.encode()), instr);
+        // imm = 0xffe: imm[10:1] = 0x3ff at [30:21], imm[11] = 1 at [20], imm[19:12] and
+        // imm[20] are zero.
+        assert_eq!(instr.encode(), 0x7ff0_01ef);
+    }
+}
@@ -13,9 +13,11 @@
 mod coverage;
 mod fuzzer;
 mod harness;
+mod instr_input;
 mod monitor;
 
 use clap::Parser;
+use libafl_bolts::core_affinity::Cores;
 
 #[derive(Parser, Default, Debug)]
 struct Arguments {
@@ -36,6 +38,24 @@ struct Arguments {
     corpus_input: String,
     #[clap(long)]
     corpus_output: Option<String>,
+    #[clap(long)]
+    max_run_timeout: Option<u64>,
+    #[clap(default_value_t = false, long)]
+    continue_on_errors: bool,
+    #[clap(default_value_t = false, long)]
+    save_errors: bool,
+    #[clap(default_value_t = String::from("queue"), long)]
+    schedule: String,
+    #[clap(default_value_t = String::from("0"), long)]
+    cores: String,
+    #[clap(default_value_t = 1337, long)]
+    broker_port: u16,
+    #[clap(default_value_t = String::from("bytes"), long)]
+    input_format: String,
+    #[clap(default_value_t = false, long)]
+    fork: bool,
+    #[clap(default_value_t = false, long)]
+    cmin: bool,
     // Run options
     #[clap(default_value_t = 1, long)]
     repeat: usize,
@@ -64,7 +84,15 @@ fn main() -> i32 {
         }
     }
 
-    harness::set_sim_env(args.coverage, args.verbose, args.max_runs, emu_args);
+    harness::set_sim_env(args.coverage, args.verbose, emu_args);
+
+    // Coverage is intentionally *not* initialized here when `--fuzzing` is set: `Launcher`
+    // forks one client process per `--cores` entry, and a `ShMem` region created in this
+    // top-level process would have every core inherit the same physical pages instead of each
+    // getting its own. The fuzzing path initializes it per core instead, inside `run_client`.
+    if !args.fuzzing {
+        coverage::cover_init();
+    }
 
     let mut has_failed = 0;
     if workloads.len() > 0 {
@@ -86,11 +114,26 @@ fn main() -> i32 {
         } else {
             Some(args.corpus_input)
         };
+        let cores = Cores::from_cmdline(&args.cores).expect("Invalid --cores specification");
+        let schedule = fuzzer::Schedule::parse(&args.schedule);
+        let input_format = fuzzer::InputFormat::parse(&args.input_format);
         fuzzer::run_fuzzer(
-            args.random_input,
-            args.max_iters,
-            corpus_input,
-            args.corpus_output,
+            fuzzer::FuzzerConfig {
+                random_input: args.random_input,
+                max_iters: args.max_iters,
+                max_runs: args.max_runs,
+                max_run_timeout: args.max_run_timeout,
+                corpus_input,
+                corpus_output: args.corpus_output,
+                continue_on_errors: args.continue_on_errors,
+                save_errors: args.save_errors,
+                schedule,
+                cores,
+                broker_port: args.broker_port,
+                fork: args.fork,
+                cmin: args.cmin,
+            },
+            input_format,
         );
     }
 
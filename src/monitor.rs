@@ -11,29 +11,109 @@
  */
 extern crate md5;
 
+use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
 
 use libafl::prelude::{BytesInput, Corpus, HasBytesVec, InMemoryCorpus, Input, OnDiskCorpus};
 use libafl::state::{HasCorpus, StdState};
-use libafl_bolts::rands::RomuDuoJrRand;
+use libafl_bolts::rands::{Rand, RomuDuoJrRand};
 
-pub fn store_testcases(
-    state: &mut StdState<
-        BytesInput,
-        InMemoryCorpus<BytesInput>,
-        RomuDuoJrRand,
-        OnDiskCorpus<BytesInput>,
-    >,
+use crate::coverage::cover_len;
+use crate::harness;
+use crate::instr_input::InstrStreamInput;
+
+/// One corpus entry's coverage footprint, used by `minimize` to run a greedy weighted
+/// set-cover over the whole corpus.
+struct Candidate<I> {
+    input: I,
+    points: Vec<usize>,
+    len: usize,
+    executions: u64,
+}
+
+/// Greedy weighted set-cover: repeatedly pick the not-yet-selected input that adds the most
+/// currently-uncovered points (ties broken by smaller input length, then fewer executions),
+/// mark its points covered, and stop when no candidate adds anything new. Mirrors LibAFL's
+/// `MinimizerScheduler`/afl-cmin behavior.
+fn minimize<I>(mut candidates: Vec<Candidate<I>>) -> (Vec<I>, usize) {
+    let mut covered: HashSet<usize> = HashSet::new();
+    let mut selected = Vec::new();
+
+    loop {
+        let best = candidates
+            .iter()
+            .enumerate()
+            .map(|(idx, c)| {
+                let new_points = c.points.iter().filter(|p| !covered.contains(p)).count();
+                (idx, new_points, c.len, c.executions)
+            })
+            .filter(|(_, new_points, _, _)| *new_points > 0)
+            .min_by(|a, b| {
+                // Most new coverage wins; ties go to the smaller, then less-executed input.
+                b.1.cmp(&a.1).then(a.2.cmp(&b.2)).then(a.3.cmp(&b.3))
+            });
+
+        let Some((idx, _, _, _)) = best else {
+            break;
+        };
+        let candidate = candidates.remove(idx);
+        covered.extend(candidate.points.iter().copied());
+        selected.push(candidate.input);
+    }
+
+    (selected, covered.len())
+}
+
+/// Shared by `store_testcases`/`store_instr_testcases`: walks a corpus, optionally minimizing
+/// it first via greedy weighted set-cover, and writes every retained entry to `output_dir`.
+/// `replay` returns the coverage points a single input hits (for minimization); `to_bytes`
+/// serializes an input to the flat on-disk format `store_testcase` writes.
+fn store_corpus<I, R>(
+    state: &mut StdState<I, InMemoryCorpus<I>, R, OnDiskCorpus<I>>,
     output_dir: String,
-) {
+    minimize_corpus: bool,
+    replay: impl Fn(&I) -> Vec<usize>,
+    to_bytes: impl Fn(&I) -> BytesInput,
+) where
+    I: Input + Clone,
+    R: Rand,
+{
     let corpus = state.corpus();
 
     let count = corpus.count();
     println!("Total corpus count: {count}");
 
+    if minimize_corpus {
+        let candidates = corpus
+            .ids()
+            .map(|id| {
+                let testcase = corpus.get(id).unwrap().borrow_mut();
+                let input = testcase.input().as_ref().unwrap().clone();
+                let executions = testcase.executions();
+                Candidate {
+                    points: replay(&input),
+                    len: to_bytes(&input).bytes().len(),
+                    executions,
+                    input,
+                }
+            })
+            .collect();
+
+        let (selected, retained) = minimize(candidates);
+        println!(
+            "Corpus minimization: {count} -> {} entries, retaining {:.3}% coverage",
+            selected.len(),
+            100.0 * retained as f64 / cover_len() as f64
+        );
+        for (i, input) in selected.iter().enumerate() {
+            store_testcase(&to_bytes(input), &output_dir, Some(i.to_string()));
+        }
+        return;
+    }
+
     for id in corpus.ids() {
-        let testcase: std::cell::RefMut<libafl::prelude::Testcase<BytesInput>> =
+        let testcase: std::cell::RefMut<libafl::prelude::Testcase<I>> =
             corpus.get(id).unwrap().borrow_mut();
         let executions = testcase.executions();
         let scheduled_count = testcase.scheduled_count();
@@ -44,10 +124,50 @@ pub fn store_testcases(
         };
         println!("Corpus {id}: executions {executions}, scheduled_count {scheduled_count}, parent_id {parent_id}");
         let x = testcase.input().as_ref().unwrap();
-        store_testcase(x, &output_dir, Some(id.to_string()));
+        store_testcase(&to_bytes(x), &output_dir, Some(id.to_string()));
     }
 }
 
+pub fn store_testcases(
+    state: &mut StdState<
+        BytesInput,
+        InMemoryCorpus<BytesInput>,
+        RomuDuoJrRand,
+        OnDiskCorpus<BytesInput>,
+    >,
+    output_dir: String,
+    minimize_corpus: bool,
+) {
+    store_corpus(
+        state,
+        output_dir,
+        minimize_corpus,
+        harness::replay_bytes_coverage,
+        BytesInput::clone,
+    );
+}
+
+/// Same as `store_testcases`, but for the `--input-format instr` corpus: each entry is
+/// serialized to its flat image before being written out.
+pub fn store_instr_testcases(
+    state: &mut StdState<
+        InstrStreamInput,
+        InMemoryCorpus<InstrStreamInput>,
+        RomuDuoJrRand,
+        OnDiskCorpus<InstrStreamInput>,
+    >,
+    output_dir: String,
+    minimize_corpus: bool,
+) {
+    store_corpus(
+        state,
+        output_dir,
+        minimize_corpus,
+        harness::replay_instr_coverage,
+        |input: &InstrStreamInput| BytesInput::new(input.to_flat_image()),
+    );
+}
+
 pub fn store_testcase(input: &BytesInput, output_dir: &String, name: Option<String>) {
     fs::create_dir_all(&output_dir).expect("Unable to create the output directory");
 